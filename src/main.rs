@@ -18,7 +18,7 @@
 //! SPDX-License-Identifier: Apache-2.0
 
 use std::fs::{self, File};
-use std::io::{self, BufWriter, Cursor, Write};
+use std::io::{self, BufWriter, Cursor, Read, Write};
 use std::path::Path;
 
 use anyhow::{bail, Context, Result};
@@ -40,6 +40,82 @@ impl<W: Write> WriteU32 for W {
 enum ImageType {
     Png,
     Jpeg,
+    Gif,
+    Bmp,
+    Tiff,
+    WebP,
+}
+
+/// Guess an [`ImageType`] by sniffing the file's magic bytes. Returns `None` if the data doesn't
+/// match any known signature, in which case the caller should fall back to the file extension.
+fn sniff_image_type(data: &[u8]) -> Option<ImageType> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(ImageType::Png)
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some(ImageType::Jpeg)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(ImageType::Gif)
+    } else if data.starts_with(b"BM") {
+        Some(ImageType::Bmp)
+    } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        Some(ImageType::Tiff)
+    } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some(ImageType::WebP)
+    } else {
+        None
+    }
+}
+
+/// Guess an [`ImageType`] from a file extension, used as a fallback when the magic bytes don't
+/// match any known signature.
+fn image_type_from_extension(path: &Path) -> Option<ImageType> {
+    match path.extension().and_then(std::ffi::OsStr::to_str) {
+        Some("png") => Some(ImageType::Png),
+        Some("jpg" | "jpeg") => Some(ImageType::Jpeg),
+        Some("gif") => Some(ImageType::Gif),
+        Some("bmp") => Some(ImageType::Bmp),
+        Some("tif" | "tiff") => Some(ImageType::Tiff),
+        Some("webp") => Some(ImageType::WebP),
+        _ => None,
+    }
+}
+
+/// The standard METADATA_BLOCK_PICTURE / ID3v2 APIC picture types, as `(value, name)` pairs.
+const PICTURE_TYPES: &[(u32, &str)] = &[
+    (0, "other"),
+    (1, "32x32 png file icon"),
+    (2, "other file icon"),
+    (3, "cover front"),
+    (4, "cover back"),
+    (5, "leaflet"),
+    (6, "media"),
+    (7, "lead artist"),
+    (8, "artist"),
+    (9, "conductor"),
+    (10, "band"),
+    (11, "composer"),
+    (12, "lyricist"),
+    (13, "recording location"),
+    (14, "during recording"),
+    (15, "during performance"),
+    (16, "video capture"),
+    (17, "fish"),
+    (18, "illustration"),
+    (19, "band logotype"),
+    (20, "publisher logotype"),
+];
+
+/// Parse a `--type` argument as either an integer or a case-insensitive picture type name.
+fn parse_picture_type(s: &str) -> Result<u32, String> {
+    if let Ok(n) = s.parse::<u32>() {
+        return Ok(n);
+    }
+
+    PICTURE_TYPES
+        .iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(s))
+        .map(|(n, _)| *n)
+        .ok_or_else(|| format!("invalid picture type {s:?} (expected a number or name)"))
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -53,10 +129,14 @@ enum OutputFormat {
 /// data structure.
 #[derive(Debug)]
 struct MetadataBlockPicture<'a> {
+    picture_type: u32,
     mime: &'a str,
+    description: &'a str,
     width: u32,
     height: u32,
     bit_depth: u32,
+    /// Number of colors used for indexed-color pictures, or 0 for non-indexed pictures.
+    num_colors: u32,
     data: &'a [u8],
 }
 
@@ -65,17 +145,13 @@ impl<'a> MetadataBlockPicture<'a> {
     ///
     /// `data` argument is the full contents of a valid PNG image and will be borrowed for as long
     /// as this `MetadataBlockPicture` exists.
-    fn from_png(data: &'a [u8]) -> Result<Self> {
+    fn from_png(data: &'a [u8], picture_type: u32, description: &'a str) -> Result<Self> {
         use png::{BitDepth, ColorType, Decoder};
 
         let decoder = Decoder::new(Cursor::new(data));
         let reader = decoder.read_info().context("PNG image parse")?;
         let info = reader.info();
 
-        // note: this ignores indexed color PNGs and treats them the same as a grayscale image in
-        // the metadata, i.e. the index color count field is left at zero. Presumably this is fine
-        // in practice. The xiph flac docs use GIF as an example of indexed color images, not PNG,
-        // and I'm not quite sure how to get the pallette size properly.
         let num_channels = match info.color_type {
             ColorType::Grayscale => 1,
             ColorType::Rgb => 3,
@@ -92,19 +168,29 @@ impl<'a> MetadataBlockPicture<'a> {
             BitDepth::Sixteen => 16,
         };
 
+        // for indexed-color images, bit_depth is the bits-per-index (same arithmetic as the
+        // other color types since num_channels is 1), and num_colors comes from the PLTE chunk.
         let bit_depth = num_channels * bits_per_channel;
+        let num_colors = if info.color_type == ColorType::Indexed {
+            info.palette.as_ref().map_or(0, |p| (p.len() / 3) as u32)
+        } else {
+            0
+        };
 
         Ok(Self {
+            picture_type,
             mime: "image/png",
+            description,
             width: info.width,
             height: info.height,
             bit_depth,
+            num_colors,
             data,
         })
     }
 
     /// Parse the info needed for picture metadata from the data of a jpeg image file.
-    fn from_jpeg(data: &'a [u8]) -> Result<Self> {
+    fn from_jpeg(data: &'a [u8], picture_type: u32, description: &'a str) -> Result<Self> {
         use jpeg_decoder::{Decoder, PixelFormat};
 
         let mut decoder = Decoder::new(Cursor::new(data));
@@ -119,25 +205,130 @@ impl<'a> MetadataBlockPicture<'a> {
         };
 
         Ok(Self {
+            picture_type,
             mime: "image/jpeg",
+            description,
             width: info.width.into(),
             height: info.height.into(),
             bit_depth,
+            num_colors: 0,
+            data,
+        })
+    }
+
+    /// Parse the info needed for picture metadata from the data of a GIF image file.
+    fn from_gif(data: &'a [u8], picture_type: u32, description: &'a str) -> Result<Self> {
+        use image::codecs::gif::GifDecoder;
+        use image::ImageDecoder;
+
+        let decoder = GifDecoder::new(Cursor::new(data)).context("GIF image parse")?;
+        let (width, height) = decoder.dimensions();
+
+        // the logical screen descriptor's packed byte (offset 10) encodes whether a global
+        // color table follows and, if so, its size as 2^(N+1) entries in its low 3 bits.
+        let (bit_depth, num_colors) = match data.get(10) {
+            Some(packed) if packed & 0x80 != 0 => {
+                let index_bits = u32::from(packed & 0x07) + 1;
+                (index_bits, 1 << index_bits)
+            }
+            _ => (decoder.color_type().bits_per_pixel().into(), 0),
+        };
+
+        Ok(Self {
+            picture_type,
+            mime: "image/gif",
+            description,
+            width,
+            height,
+            bit_depth,
+            num_colors,
+            data,
+        })
+    }
+
+    /// Parse the info needed for picture metadata from the data of a BMP image file.
+    fn from_bmp(data: &'a [u8], picture_type: u32, description: &'a str) -> Result<Self> {
+        use image::codecs::bmp::BmpDecoder;
+        use image::ImageDecoder;
+
+        let decoder = BmpDecoder::new(Cursor::new(data)).context("BMP image parse")?;
+        let (width, height) = decoder.dimensions();
+        let bit_depth = decoder.color_type().bits_per_pixel().into();
+
+        Ok(Self {
+            picture_type,
+            mime: "image/bmp",
+            description,
+            width,
+            height,
+            bit_depth,
+            num_colors: 0,
+            data,
+        })
+    }
+
+    /// Parse the info needed for picture metadata from the data of a TIFF image file.
+    fn from_tiff(data: &'a [u8], picture_type: u32, description: &'a str) -> Result<Self> {
+        use image::codecs::tiff::TiffDecoder;
+        use image::ImageDecoder;
+
+        let decoder = TiffDecoder::new(Cursor::new(data)).context("TIFF image parse")?;
+        let (width, height) = decoder.dimensions();
+        let bit_depth = decoder.color_type().bits_per_pixel().into();
+
+        Ok(Self {
+            picture_type,
+            mime: "image/tiff",
+            description,
+            width,
+            height,
+            bit_depth,
+            num_colors: 0,
+            data,
+        })
+    }
+
+    /// Parse the info needed for picture metadata from the data of a WebP image file.
+    fn from_webp(data: &'a [u8], picture_type: u32, description: &'a str) -> Result<Self> {
+        use image::codecs::webp::WebPDecoder;
+        use image::ImageDecoder;
+
+        let decoder = WebPDecoder::new(Cursor::new(data)).context("WebP image parse")?;
+        let (width, height) = decoder.dimensions();
+        let bit_depth = decoder.color_type().bits_per_pixel().into();
+
+        Ok(Self {
+            picture_type,
+            mime: "image/webp",
+            description,
+            width,
+            height,
+            bit_depth,
+            num_colors: 0,
             data,
         })
     }
 
     /// Parse the info needed for picture metadata using one of the supported types.
-    fn from_type(data: &'a [u8], image_type: ImageType) -> Result<Self> {
+    fn from_type(
+        data: &'a [u8],
+        image_type: ImageType,
+        picture_type: u32,
+        description: &'a str,
+    ) -> Result<Self> {
         match image_type {
-            ImageType::Png => Self::from_png(data),
-            ImageType::Jpeg => Self::from_jpeg(data),
+            ImageType::Png => Self::from_png(data, picture_type, description),
+            ImageType::Jpeg => Self::from_jpeg(data, picture_type, description),
+            ImageType::Gif => Self::from_gif(data, picture_type, description),
+            ImageType::Bmp => Self::from_bmp(data, picture_type, description),
+            ImageType::Tiff => Self::from_tiff(data, picture_type, description),
+            ImageType::WebP => Self::from_webp(data, picture_type, description),
         }
     }
 
     /// Write the METADATA_BLOCK_PICTURE header and data to the given writer
     fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
-        w.write_u32b(3)?; // type: cover(front)
+        w.write_u32b(self.picture_type)?;
         w.write_u32b(
             self.mime
                 .len()
@@ -145,29 +336,146 @@ impl<'a> MetadataBlockPicture<'a> {
                 .expect("MIME type length overflow"),
         )?;
         w.write_all(self.mime.as_bytes())?;
-        w.write_u32b(0)?; // description length
+        w.write_u32b(
+            self.description
+                .len()
+                .try_into()
+                .expect("description length overflow"),
+        )?;
+        w.write_all(self.description.as_bytes())?;
         w.write_u32b(self.width)?;
         w.write_u32b(self.height)?;
         w.write_u32b(self.bit_depth)?;
-        w.write_u32b(0)?; // index color count, not used for png/jpg
+        w.write_u32b(self.num_colors)?;
         w.write_u32b(self.data.len().try_into().expect("data length overflow"))?;
         w.write_all(self.data)?;
         Ok(())
     }
 }
 
+/// A METADATA_BLOCK_PICTURE that's been parsed back out of its binary form, produced by
+/// `--decode`. Unlike [`MetadataBlockPicture`], this owns its data since it's built by parsing
+/// a buffer rather than borrowing from a source image file.
+#[derive(Debug)]
+struct DecodedPicture {
+    picture_type: u32,
+    mime: String,
+    description: String,
+    width: u32,
+    height: u32,
+    bit_depth: u32,
+    num_colors: u32,
+    data: Vec<u8>,
+}
+
+/// Read a big-endian u32 from the front of `buf`, advancing past it.
+fn take_u32(buf: &mut &[u8]) -> Result<u32> {
+    if buf.len() < 4 {
+        bail!("unexpected end of data while reading a 32-bit field");
+    }
+    let (head, rest) = buf.split_at(4);
+    *buf = rest;
+    Ok(u32::from_be_bytes(head.try_into().unwrap()))
+}
+
+/// Read `len` bytes from the front of `buf`, advancing past them, erroring if `buf` is too short.
+fn take_bytes<'a>(buf: &mut &'a [u8], len: usize, field: &str) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        bail!(
+            "{field} length ({len}) exceeds the remaining {} bytes of data",
+            buf.len()
+        );
+    }
+    let (head, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(head)
+}
+
+/// Read a u32 byte length followed by that many bytes of UTF-8 text from the front of `buf`.
+fn take_string(buf: &mut &[u8], field: &str) -> Result<String> {
+    let len: usize = take_u32(buf)?
+        .try_into()
+        .expect("length overflow on this platform");
+    let bytes = take_bytes(buf, len, field)?;
+    String::from_utf8(bytes.to_vec()).with_context(|| format!("{field} is not valid UTF-8"))
+}
+
+impl DecodedPicture {
+    /// Parse a binary METADATA_BLOCK_PICTURE, validating that every declared length fits within
+    /// the remaining buffer.
+    fn parse(mut buf: &[u8]) -> Result<Self> {
+        let picture_type = take_u32(&mut buf).context("picture type")?;
+        let mime = take_string(&mut buf, "MIME type")?;
+        let description = take_string(&mut buf, "description")?;
+        let width = take_u32(&mut buf).context("width")?;
+        let height = take_u32(&mut buf).context("height")?;
+        let bit_depth = take_u32(&mut buf).context("bit depth")?;
+        let num_colors = take_u32(&mut buf).context("color count")?;
+        let data_len: usize = take_u32(&mut buf)
+            .context("data length")?
+            .try_into()
+            .expect("length overflow on this platform");
+        let data = take_bytes(&mut buf, data_len, "picture data")?.to_vec();
+
+        Ok(Self {
+            picture_type,
+            mime,
+            description,
+            width,
+            height,
+            bit_depth,
+            num_colors,
+            data,
+        })
+    }
+}
+
+/// Strip an optional `;FFMETADATA1\nMETADATA_BLOCK_PICTURE=` wrapper from `input`, then base64
+/// decode it if it looks like base64 text, otherwise assume it's already raw binary.
+fn decode_wrapped_picture(input: &[u8]) -> Result<DecodedPicture> {
+    let payload = input
+        .strip_prefix(b";FFMETADATA1\n")
+        .and_then(|rest| rest.strip_prefix(b"METADATA_BLOCK_PICTURE="))
+        .unwrap_or(input);
+
+    let looks_like_base64 = !payload.is_empty()
+        && payload
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'=' | b'\r' | b'\n'));
+
+    let binary = if looks_like_base64 {
+        let cleaned: Vec<u8> = payload
+            .iter()
+            .copied()
+            .filter(u8::is_ascii_graphic)
+            .collect();
+        base64::decode(cleaned).context("failed to base64-decode input")?
+    } else {
+        payload.to_vec()
+    };
+
+    DecodedPicture::parse(&binary)
+}
+
 fn parse_args() -> ArgMatches {
     Command::new("ogg-coverart")
         .version(crate_version!())
         .about("Generate FLAC/OGG METADATA_BLOCK_PICTURE tag data from an image")
-        .override_usage("ogg-coverart [OPTIONS] {-f | -b | -B} [-o OUTPUT] INPUT")
+        .override_usage(
+            "ogg-coverart [OPTIONS] {-f | -b | -B} [-o OUTPUT] INPUT\n       \
+             ogg-coverart --decode [-o OUTPUT] [INPUT]",
+        )
         .setting(AppSettings::DeriveDisplayOrder)
         .arg(
             Arg::new("input")
-                .required(true)
                 .allow_invalid_utf8(true)
                 .value_name("INPUT")
-                .help("Input image file"),
+                .help("Input image file, or with --decode the METADATA_BLOCK_PICTURE to read (default stdin)"),
+        )
+        .arg(
+            Arg::new("decode")
+                .long("decode")
+                .help("Decode mode: extract the image from a METADATA_BLOCK_PICTURE instead of encoding one"),
         )
         .arg(
             Arg::new("output")
@@ -199,7 +507,25 @@ fn parse_args() -> ArgMatches {
             ArgGroup::new("format")
                 .args(&["fmt_ffmetadata", "fmt_bin", "fmt_base64"])
                 .multiple(false)
-                .required(true),
+                .required(false),
+        )
+        .arg(
+            Arg::new("picture_type")
+                .short('t')
+                .long("type")
+                .takes_value(true)
+                .value_name("TYPE")
+                .default_value("cover front")
+                .validator(|s| parse_picture_type(s).map(|_| ()))
+                .help("Picture type, as a number or name (e.g. 3 or \"cover front\")"),
+        )
+        .arg(
+            Arg::new("description")
+                .short('d')
+                .long("description")
+                .takes_value(true)
+                .value_name("TEXT")
+                .help("Picture description text"),
         )
         .arg(
             Arg::new("force_png")
@@ -222,23 +548,59 @@ fn parse_args() -> ArgMatches {
         .get_matches()
 }
 
-fn run() -> Result<()> {
-    let args = parse_args();
+/// Open the `-o/--output` destination, or stdout if omitted or set to `-`.
+fn open_output(args: &ArgMatches) -> Result<Box<dyn Write>> {
+    match args.value_of("output") {
+        None | Some("-") => Ok(Box::new(io::stdout())),
+        Some(path) => Ok(Box::new(BufWriter::new(
+            File::create(path).context("failed to create output file")?,
+        ))),
+    }
+}
+
+/// Read and decode a METADATA_BLOCK_PICTURE from INPUT (or stdin), writing the embedded image
+/// bytes back out.
+fn run_decode(args: &ArgMatches) -> Result<()> {
+    let input_data = match args.value_of_os("input") {
+        Some(path) if path != "-" => fs::read(path).context("failed reading input file")?,
+        _ => {
+            let mut buf = Vec::new();
+            io::stdin()
+                .read_to_end(&mut buf)
+                .context("failed reading stdin")?;
+            buf
+        }
+    };
+
+    let picture =
+        decode_wrapped_picture(&input_data).context("failed to parse METADATA_BLOCK_PICTURE")?;
+
+    open_output(args)?
+        .write_all(&picture.data)
+        .context("failed writing output")
+}
+
+fn run_encode(args: &ArgMatches) -> Result<()> {
+    let input_path = Path::new(
+        args.value_of_os("input")
+            .context("the following required argument was not provided: INPUT")?,
+    );
+    let data = fs::read(input_path).context("failed reading input file")?;
 
-    let input_path = Path::new(args.value_of_os("input").unwrap());
     let input_type = if args.is_present("force_png") {
         ImageType::Png
     } else if args.is_present("force_jpeg") {
         ImageType::Jpeg
+    } else if let Some(t) = sniff_image_type(&data) {
+        t
+    } else if let Some(t) = image_type_from_extension(input_path) {
+        t
     } else {
-        match input_path.extension().and_then(std::ffi::OsStr::to_str) {
-            Some("png") => ImageType::Png,
-            Some("jpg" | "jpeg") => ImageType::Jpeg,
-            _ => bail!(
-                "can't determine image type (missing or unrecognized file extension)\n\
-                 Use the --png or --jpeg flag to manually set the image format"
-            ),
-        }
+        bail!(
+            "can't determine image type (unrecognized signature and missing or unrecognized \
+             file extension)\n\
+             Use the --png or --jpeg flag to manually set the image format"
+        )
     };
 
     let out_fmt = if args.is_present("fmt_bin") {
@@ -248,19 +610,24 @@ fn run() -> Result<()> {
     } else if args.is_present("fmt_ffmetadata") {
         OutputFormat::FFMetadata
     } else {
-        unreachable!()
+        bail!("one of -f/--ffmetadata, -b/--binary, or -B/--base64 is required")
     };
 
-    let data = fs::read(input_path).context("failed reading input file")?;
-    let meta =
-        MetadataBlockPicture::from_type(&data, input_type).context("failed to parse input file")?;
+    let picture_type = parse_picture_type(args.value_of("picture_type").unwrap())
+        .expect("validator should have rejected this already");
+    if picture_type > 255 {
+        eprintln!(
+            "Warning: picture type {picture_type} is larger than a byte; some tools may not handle \
+             values above 255 correctly"
+        );
+    }
 
-    let mut out: Box<dyn Write> = match args.value_of("output") {
-        None | Some("-") => Box::new(io::stdout()),
-        Some(path) => Box::new(BufWriter::new(
-            File::create(path).context("failed to create output file")?,
-        )),
-    };
+    let description = args.value_of("description").unwrap_or("");
+
+    let meta = MetadataBlockPicture::from_type(&data, input_type, picture_type, description)
+        .context("failed to parse input file")?;
+
+    let mut out = open_output(args)?;
 
     match out_fmt {
         OutputFormat::Binary => meta.write_to(&mut out)?,
@@ -280,6 +647,15 @@ fn run() -> Result<()> {
     Ok(())
 }
 
+fn run() -> Result<()> {
+    let args = parse_args();
+    if args.is_present("decode") {
+        run_decode(&args)
+    } else {
+        run_encode(&args)
+    }
+}
+
 fn main() {
     if let Err(err) = run() {
         eprintln!("Error: {err:#}");